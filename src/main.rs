@@ -1,9 +1,13 @@
 mod proc;
 mod process;
+mod serialize;
 
 use maplit::btreeset;
+use proc::maps::Map;
+use proc::pagemap::Page;
 use rayon::prelude::*;
 use process::Process;
+use serialize::{FromReader, ToWriter};
 
 #[macro_use]
 extern crate anyhow;
@@ -14,6 +18,9 @@ extern crate bitfield;
 use anyhow::Result;
 use std::{
     collections::{BTreeMap, BTreeSet},
+    fs::File,
+    thread,
+    time::Duration,
 };
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq)]
@@ -22,46 +29,114 @@ struct PageRange {
     to: u64,
 }
 
-fn compress(pages: BTreeMap<u64, BTreeSet<Process>>) -> BTreeMap<PageRange, BTreeSet<Process>> {
+/// How a shared physical page is backed, as determined from `/proc/kpageflags` and
+/// `/proc/kpagecount`. A per-process pagemap entry alone cannot tell these apart.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum ShareKind {
+    FileBacked,
+    AnonymousShared,
+    KsmMerged,
+    /// Not actually shared (map count <= 1), or `/proc/kpageflags` couldn't be read
+    /// (requires root).
+    Unknown,
+}
+
+fn classify_share_kind(page_frame_number: u64) -> ShareKind {
+    let count = match proc::kpageflags::count(page_frame_number) {
+        Ok(count) => count,
+        Err(_) => return ShareKind::Unknown,
+    };
+
+    if count <= 1 {
+        return ShareKind::Unknown;
+    }
+
+    match proc::kpageflags::flags(page_frame_number) {
+        Ok(flags) if flags.ksm() => ShareKind::KsmMerged,
+        Ok(flags) if flags.anon() => ShareKind::AnonymousShared,
+        Ok(_) => ShareKind::FileBacked,
+        Err(_) => ShareKind::Unknown,
+    }
+}
+
+/// A physical page frame, plus how many consecutive `4096`-byte frames it actually spans.
+/// Huge-page-backed mappings only contribute a single pagemap entry per compound page (see
+/// `Map::page_offsets()`), so that entry's frame span is `Map::frames_per_page()` rather
+/// than the usual 1.
+type FrameMap = BTreeMap<u64, (u64, BTreeSet<Process>)>;
+
+/// Merges `pages` into the widest possible `PageRange`s, only coalescing two adjacent
+/// frames when `classify` reports the same `ShareKind` for both. Two mappings with nothing
+/// to do with each other (one file-backed, one KSM-merged, say) can easily land on adjacent
+/// physical frames, so merging on frame contiguity alone would let one sampled `ShareKind`
+/// mislabel part of the resulting range -- splitting on every `ShareKind` change keeps each
+/// returned range honestly uniform.
+fn compress(
+    pages: FrameMap,
+    classify: impl Fn(u64) -> ShareKind,
+) -> BTreeMap<PageRange, (ShareKind, BTreeSet<Process>)> {
     let mut ret = BTreeMap::new();
 
     let mut start = 0;
-    let mut prev = 0;
+    let mut prev_end = 0;
+    let mut prev_kind = ShareKind::Unknown;
+    let mut pids = BTreeSet::new();
+
+    for (addr, (span, frame_pids)) in pages {
+        let kind = classify(addr);
 
-    for (addr, pids) in pages {
         if start == 0 {
             start = addr;
-            prev = addr;
+            prev_end = addr + span;
+            prev_kind = kind;
+            pids = frame_pids;
             continue;
         }
 
-        if prev == addr - 1 {
-            prev += 1;
+        if prev_end == addr && prev_kind == kind {
+            prev_end += span;
+            pids.extend(frame_pids);
             continue;
         }
 
         ret.insert(
-            PageRange {
-                from: start,
-                to: addr,
-            },
-            pids,
+            PageRange { from: start, to: prev_end },
+            (prev_kind, std::mem::take(&mut pids)),
         );
-        start = 0;
+        start = addr;
+        prev_end = addr + span;
+        prev_kind = kind;
+        pids = frame_pids;
+    }
+
+    if start != 0 {
+        ret.insert(PageRange { from: start, to: prev_end }, (prev_kind, pids));
     }
 
     ret
 }
 
-fn all_pages(procs: &Vec<Process>) -> BTreeMap<u64, BTreeSet<Process>> {
+fn all_pages(procs: &Vec<Process>, filter: impl Fn(&proc::maps::Map) -> bool + Sync) -> FrameMap {
     procs
         .par_iter()
         .map(|proc| {
-            let mut page_map: BTreeMap<u64, BTreeSet<Process>> = BTreeMap::new();
-            let maps = proc
-                .read_pages()
-                .expect(&format!("failed to read pages for pid {}", proc.pid));
+            let mut page_map: FrameMap = BTreeMap::new();
+            // A process can exit between `process::all()` listing it and us reaching it
+            // here -- that shouldn't abort the whole report, just like `snapshot()` already
+            // tolerates a process disappearing by skipping it.
+            let maps = match proc.read_pages() {
+                Ok(maps) => maps,
+                Err(e) => {
+                    eprintln!("failed to read pages for pid {}: {}", proc.pid, e);
+                    return page_map;
+                }
+            };
             for (map, pages) in maps {
+                if !filter(&map) {
+                    continue;
+                }
+
+                let frame_span = map.frames_per_page();
                 for page in pages {
                     if !page.in_ram() {
                         continue;
@@ -69,17 +144,150 @@ fn all_pages(procs: &Vec<Process>) -> BTreeMap<u64, BTreeSet<Process>> {
 
                     page_map
                         .entry(page.page_frame_number())
-                        .or_insert_with(|| btreeset! {})
+                        .or_insert_with(|| (frame_span, btreeset! {}))
+                        .1
                         .insert(proc.to_owned());
                 }
             }
             page_map
         })
         .reduce(
-            || BTreeMap::new(),
+            BTreeMap::new,
+            |mut a, b| {
+                for (k, (span, procs)) in b {
+                    let entry = a.entry(k).or_insert_with(|| (span, BTreeSet::new()));
+                    entry.1.extend(procs);
+                }
+                a
+            },
+        )
+}
+
+/// A process's working set as seen via `clear_refs`/soft-dirty: how many pages were
+/// touched over the interval between two snapshots, and the virtual address ranges they
+/// fall in.
+#[derive(Debug)]
+struct DirtiedPages {
+    count: usize,
+    ranges: Vec<PageRange>,
+}
+
+type ProcessSnapshot = BTreeMap<Map, Vec<Page>>;
+
+fn snapshot(procs: &[Process]) -> BTreeMap<Process, ProcessSnapshot> {
+    procs
+        .iter()
+        .filter_map(|proc| proc.read_pages().ok().map(|pages| (proc.to_owned(), pages)))
+        .collect()
+}
+
+/// Coalesces dirtied addresses into ranges, respecting each address's own page size --
+/// entries from a huge-page-backed mapping span far more than the default page size, and
+/// mixing that up with a fixed stride would both misplace and wrongly split/merge ranges.
+fn compress_addrs(addrs: BTreeMap<u64, u64>) -> Vec<PageRange> {
+    let mut ret = Vec::new();
+    let mut range: Option<PageRange> = None;
+
+    for (addr, page_size) in addrs {
+        range = Some(match range {
+            Some(r) if addr == r.to => PageRange { from: r.from, to: addr + page_size },
+            Some(r) => {
+                ret.push(r);
+                PageRange { from: addr, to: addr + page_size }
+            }
+            None => PageRange { from: addr, to: addr + page_size },
+        });
+    }
+
+    if let Some(r) = range {
+        ret.push(r);
+    }
+
+    ret
+}
+
+fn diff_snapshots(
+    before: &BTreeMap<Process, ProcessSnapshot>,
+    after: &BTreeMap<Process, ProcessSnapshot>,
+) -> BTreeMap<Process, DirtiedPages> {
+    let mut ret = BTreeMap::new();
+
+    for (proc, after_maps) in after {
+        let before_maps = match before.get(proc) {
+            Some(maps) => maps,
+            None => continue,
+        };
+
+        // Maps addr -> the page size it was dirtied at, so a mapping backed by huge pages
+        // strides correctly instead of assuming every page is the default size.
+        let mut dirtied: BTreeMap<u64, u64> = BTreeMap::new();
+        for (map, after_pages) in after_maps {
+            let before_pages = match before_maps.get(map) {
+                Some(pages) => pages,
+                None => continue,
+            };
+
+            let page_size = map.kernel_page_size();
+            for (i, (before_page, after_page)) in before_pages.iter().zip(after_pages).enumerate() {
+                if !before_page.is_soft_dirty() && after_page.is_soft_dirty() {
+                    dirtied.insert(map.address_range.start + i as u64 * page_size, page_size);
+                }
+            }
+        }
+
+        ret.insert(
+            proc.to_owned(),
+            DirtiedPages {
+                count: dirtied.len(),
+                ranges: compress_addrs(dirtied),
+            },
+        );
+    }
+
+    ret
+}
+
+fn all_swapped_pages(
+    procs: &Vec<Process>,
+    filter: impl Fn(&Map) -> bool + Sync,
+) -> BTreeMap<(u64, u64), BTreeSet<Process>> {
+    procs
+        .par_iter()
+        .map(|proc| {
+            let mut swap_map: BTreeMap<(u64, u64), BTreeSet<Process>> = BTreeMap::new();
+            // A process can exit between `process::all()` listing it and us reaching it
+            // here -- that shouldn't abort the whole report, just like `snapshot()` already
+            // tolerates a process disappearing by skipping it.
+            let maps = match proc.read_pages() {
+                Ok(maps) => maps,
+                Err(e) => {
+                    eprintln!("failed to read pages for pid {}: {}", proc.pid, e);
+                    return swap_map;
+                }
+            };
+            for (map, pages) in maps {
+                if !filter(&map) {
+                    continue;
+                }
+
+                for page in pages {
+                    if !page.in_swap() {
+                        continue;
+                    }
+
+                    swap_map
+                        .entry((page.swap_type(), page.swap_offset()))
+                        .or_insert_with(|| btreeset! {})
+                        .insert(proc.to_owned());
+                }
+            }
+            swap_map
+        })
+        .reduce(
+            BTreeMap::new,
             |mut a, b| {
                 for (k, v) in b {
-                    let set = a.entry(k).or_insert_with(|| BTreeSet::new());
+                    let set = a.entry(k).or_insert_with(BTreeSet::new);
                     set.extend(v);
                 }
                 a
@@ -87,18 +295,185 @@ fn all_pages(procs: &Vec<Process>) -> BTreeMap<u64, BTreeSet<Process>> {
         )
 }
 
-fn main() -> Result<()> {
+/// Mappings to include in the sharing report. Read-only private mappings (`r--p`/`r-xp`,
+/// e.g. shared library text/rodata) are the dominant real-world source of genuinely shared
+/// physical pages, since they're never written and so never copy-on-write out of sharing --
+/// unlike writable-private mappings, which stop being shared the moment they are. So by
+/// default every mapping is considered; `writable_or_shared_only` is an opt-in narrowing to
+/// just the COW-relevant writable/explicitly-shared subset.
+fn sharing_filter(writable_or_shared_only: bool) -> impl Fn(&Map) -> bool + Sync + Copy {
+    move |map: &Map| !writable_or_shared_only || map.is_writable() || map.is_shared()
+}
+
+fn print_sharing_report(writable_or_shared_only: bool) -> Result<()> {
     let processes = process::all()?;
-    let all_pages = all_pages(&processes);
+    let filter = sharing_filter(writable_or_shared_only);
+    let all_pages = all_pages(&processes, filter);
 
-    for (page_range, pids) in compress(all_pages) {
+    for (page_range, (share_kind, pids)) in compress(all_pages, classify_share_kind) {
         println!(
-            "0x{:x}-0x{:x} -- {:?}",
+            "0x{:x}-0x{:x} -- {:?} -- {:?}",
             page_range.from * 4096,
             page_range.to * 4096,
+            share_kind,
             pids
         );
     }
 
+    // Pages can also be shared while swapped out -- they just share a (type, offset) in
+    // the swap area instead of a physical page frame number.
+    let swapped_pages = all_swapped_pages(&processes, filter);
+    for ((swap_type, swap_offset), pids) in swapped_pages {
+        if pids.len() < 2 {
+            continue;
+        }
+
+        println!("swap {}:{} -- {:?}", swap_type, swap_offset, pids);
+    }
+
     Ok(())
 }
+
+fn print_working_set_report(interval: Duration) -> Result<()> {
+    let processes = process::all()?;
+    for proc in &processes {
+        // A process can exit between `process::all()` listing it and us reaching it here --
+        // that shouldn't abort the whole report, just like `snapshot()` below already
+        // tolerates a process disappearing by skipping it.
+        if let Err(e) = proc.clear_soft_dirty() {
+            eprintln!("failed to clear soft-dirty bits for pid {}: {}", proc.pid, e);
+        }
+    }
+
+    let before = snapshot(&processes);
+    thread::sleep(interval);
+    let after = snapshot(&processes);
+
+    for (proc, dirtied) in diff_snapshots(&before, &after) {
+        println!("pid {} -- {} pages dirtied -- {:?}", proc.pid, dirtied.count, dirtied.ranges);
+    }
+
+    Ok(())
+}
+
+fn dump_sharing_report(path: &str, writable_or_shared_only: bool) -> Result<()> {
+    let processes = process::all()?;
+    let all_pages = all_pages(&processes, sharing_filter(writable_or_shared_only));
+    let report = compress(all_pages, classify_share_kind);
+
+    let mut file = File::create(path)?;
+    report.to_writer(&mut file)?;
+
+    Ok(())
+}
+
+fn print_dumped_report(path: &str) -> Result<()> {
+    let mut file = File::open(path)?;
+    let report: BTreeMap<PageRange, (ShareKind, BTreeSet<Process>)> =
+        FromReader::from_reader(&mut file)?;
+
+    for (page_range, (share_kind, pids)) in report {
+        println!(
+            "0x{:x}-0x{:x} -- {:?} -- {:?}",
+            page_range.from * 4096,
+            page_range.to * 4096,
+            share_kind,
+            pids
+        );
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("working-set") => {
+            let interval_secs: u64 = args.next().and_then(|s| s.parse().ok()).unwrap_or(5);
+            print_working_set_report(Duration::from_secs(interval_secs))
+        }
+        Some("dump") => {
+            let path = args.next().ok_or_else(|| anyhow!("dump requires a file path"))?;
+            dump_sharing_report(&path, has_flag(&mut args, "--writable-or-shared-only"))
+        }
+        Some("load") => {
+            let path = args.next().ok_or_else(|| anyhow!("load requires a file path"))?;
+            print_dumped_report(&path)
+        }
+        Some("--writable-or-shared-only") => print_sharing_report(true),
+        _ => print_sharing_report(false),
+    }
+}
+
+fn has_flag(args: &mut impl Iterator<Item = String>, flag: &str) -> bool {
+    args.next().as_deref() == Some(flag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_compress_splits_on_share_kind_change() {
+        // Two unrelated mappings landing on adjacent PFNs: the first two frames are
+        // file-backed, the next two are KSM-merged. They must not be reported as one range.
+        let mut pages: FrameMap = BTreeMap::new();
+        pages.insert(1, (1, btreeset! { Process::from_parts(1, "/a".to_owned()) }));
+        pages.insert(2, (1, btreeset! { Process::from_parts(1, "/a".to_owned()) }));
+        pages.insert(3, (1, btreeset! { Process::from_parts(2, "/b".to_owned()) }));
+        pages.insert(4, (1, btreeset! { Process::from_parts(2, "/b".to_owned()) }));
+
+        let ranges = compress(pages, |addr| {
+            if addr < 3 {
+                ShareKind::FileBacked
+            } else {
+                ShareKind::KsmMerged
+            }
+        });
+
+        assert_eq!(
+            ranges,
+            BTreeMap::from([
+                (
+                    PageRange { from: 1, to: 3 },
+                    (
+                        ShareKind::FileBacked,
+                        btreeset! { Process::from_parts(1, "/a".to_owned()) },
+                    ),
+                ),
+                (
+                    PageRange { from: 3, to: 5 },
+                    (
+                        ShareKind::KsmMerged,
+                        btreeset! { Process::from_parts(2, "/b".to_owned()) },
+                    ),
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_compress_addrs_huge_page_stride() {
+        let page_size = 4096u64;
+        let huge_page_size = 2 * 1024 * 1024u64;
+
+        let mut addrs = BTreeMap::new();
+        addrs.insert(0, page_size);
+        addrs.insert(page_size, page_size);
+        // Three contiguous 2 MiB huge pages, dirtied at their own stride rather than 4096.
+        addrs.insert(huge_page_size, huge_page_size);
+        addrs.insert(2 * huge_page_size, huge_page_size);
+        addrs.insert(3 * huge_page_size, huge_page_size);
+
+        let ranges = compress_addrs(addrs);
+
+        assert_eq!(
+            ranges,
+            vec![
+                PageRange { from: 0, to: 2 * page_size },
+                PageRange { from: huge_page_size, to: 4 * huge_page_size },
+            ]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+};
+
+bitfield! {
+    #[derive(PartialEq)]
+    pub struct PageFlags(u64);
+    pub locked, _: 0;
+    pub referenced, _: 2;
+    pub uptodate, _: 3;
+    pub dirty, _: 4;
+    pub lru, _: 5;
+    pub active, _: 6;
+    pub slab, _: 7;
+    pub buddy, _: 10;
+    pub mmap, _: 11;
+    pub anon, _: 12;
+    pub swapcache, _: 13;
+    pub swapbacked, _: 14;
+    pub compound_head, _: 15;
+    pub compound_tail, _: 16;
+    pub huge, _: 17;
+    pub ksm, _: 21;
+    pub thp, _: 22;
+}
+
+impl Debug for PageFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PageFlags {{ locked: {}, referenced: {}, uptodate: {}, dirty: {}, lru: {}, active: {}, slab: {}, buddy: {}, mmap: {}, anon: {}, swapcache: {}, swapbacked: {}, compound_head: {}, compound_tail: {}, huge: {}, ksm: {}, thp: {} }}", self.locked(), self.referenced(), self.uptodate(), self.dirty(), self.lru(), self.active(), self.slab(), self.buddy(), self.mmap(), self.anon(), self.swapcache(), self.swapbacked(), self.compound_head(), self.compound_tail(), self.huge(), self.ksm(), self.thp())
+    }
+}
+
+/// Reads and decodes the `/proc/kpageflags` entry for `page_frame_number`. Requires root.
+pub fn flags(page_frame_number: u64) -> Result<PageFlags> {
+    Ok(PageFlags(read_entry("/proc/kpageflags", page_frame_number)?))
+}
+
+/// Reads the system-wide mapping count for `page_frame_number` from `/proc/kpagecount`.
+/// Requires root.
+pub fn count(page_frame_number: u64) -> Result<u64> {
+    read_entry("/proc/kpagecount", page_frame_number)
+}
+
+fn read_entry(path: &str, page_frame_number: u64) -> Result<u64> {
+    let offset = page_frame_number * 8;
+    let file = File::open(path).context(format!("failed to open {}", path))?;
+    let mut read = BufReader::new(file);
+    let mut buf = [0u8; 8];
+
+    read.seek(SeekFrom::Start(offset))
+        .context(format!("failed to seek to pfn {} in {}", page_frame_number, path))?;
+    read.read_exact(&mut buf)
+        .context(format!("failed to read pfn {} from {}", page_frame_number, path))?;
+
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_zero() {
+        let flags = PageFlags(0);
+        assert_eq!(flags.anon(), false);
+        assert_eq!(flags.ksm(), false);
+        assert_eq!(flags.huge(), false);
+    }
+
+    #[test]
+    fn test_anon_and_ksm() {
+        let flags = PageFlags((1 << 12) | (1 << 21));
+        assert_eq!(flags.anon(), true);
+        assert_eq!(flags.ksm(), true);
+        assert_eq!(flags.huge(), false);
+    }
+}
@@ -11,26 +11,60 @@ use std::{
 };
 use lasso::{ThreadedRodeo, MiniSpur};
 use crate::process::Process;
+use crate::proc::smaps;
 
 lazy_static! {
     static ref MAP_RE: Regex = Regex::new(r"^(?P<from>[0-9a-f]+)-(?P<to>[0-9a-f]+)\s+(?P<permissions>....)\s+(?P<offset>[0-9a-f]+)\s+(?P<dev>..:..)\s+(?P<inode>[0-9]+)\s*(?:(?P<path>.+))?$").unwrap();
     static ref INTERNER: Arc<ThreadedRodeo<MiniSpur>> = Arc::new(ThreadedRodeo::new());
 }
 
+fn default_page_size() -> u64 {
+    sysconf(PAGE_SIZE).unwrap().unwrap() as u64
+}
+
 #[derive(Debug, PartialEq, Ord, PartialOrd, Eq, Copy, Clone, Hash)]
 pub struct Range {
-    start: u64,
-    end: u64,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+}
+
+/// The four-character permission field from a `/proc/<pid>/maps` line (e.g. `"rwxp"`).
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub shared: bool,
+}
+
+impl Permissions {
+    fn parse(s: &str) -> Result<Self> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 4 {
+            return Err(anyhow!("permissions field must be 4 characters, got \"{}\"", s));
+        }
+
+        Ok(Self {
+            read: bytes[0] == b'r',
+            write: bytes[1] == b'w',
+            execute: bytes[2] == b'x',
+            shared: bytes[3] == b's',
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub struct Map {
     pub address_range: Range,
-    permissions: MiniSpur,
+    permissions: Permissions,
     pub offset: u64,
     device: MiniSpur,
     pub inode: u64,
     path: Option<MiniSpur>,
+    /// The size in bytes of the pages backing this mapping, per `/proc/<pid>/smaps`.
+    /// Equal to the system page size for ordinary mappings, or 2 MiB/1 GiB for mappings
+    /// backed by transparent or explicit huge pages.
+    kernel_page_size: u64,
 }
 
 impl Hash for Map {
@@ -42,9 +76,7 @@ impl Hash for Map {
 
 impl PartialOrd for Map {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.address_range
-            .start
-            .partial_cmp(&other.address_range.start)
+        Some(self.cmp(other))
     }
 }
 
@@ -59,6 +91,7 @@ impl Ord for Map {
 pub struct PageOffsets {
     start: u64,
     end: u64,
+    stride: u64,
 }
 
 impl Iterator for PageOffsets {
@@ -68,25 +101,63 @@ impl Iterator for PageOffsets {
         if current > self.end {
             None
         } else {
-            self.start += 8;
+            self.start += self.stride;
             Some(current)
         }
     }
 }
 
 impl Map {
+    /// Offsets into `/proc/<pid>/pagemap` (8 bytes per page) to read for this mapping.
+    /// For huge-page-backed mappings this only visits the first pagemap slot of each
+    /// compound page, since every other slot in it describes the same physical page.
     pub fn page_offsets(&self) -> PageOffsets {
         let u64_size = size_of::<u64>() as u64;
-        let page_size = sysconf(PAGE_SIZE).unwrap().unwrap() as u64;
+        let page_size = default_page_size();
         PageOffsets {
             start: (self.address_range.start / page_size * u64_size),
             end: (self.address_range.end / page_size * u64_size) - u64_size,
+            stride: u64_size * self.frames_per_page(),
         }
     }
 
     pub fn path(&self) -> Option<&str> {
         self.path.map(|path| INTERNER.resolve(&path))
     }
+
+    pub fn kernel_page_size(&self) -> u64 {
+        self.kernel_page_size
+    }
+
+    pub fn is_huge_page(&self) -> bool {
+        self.kernel_page_size > default_page_size()
+    }
+
+    /// How many system-page-sized pagemap slots a single huge page of this mapping spans.
+    /// 1 for ordinary mappings.
+    pub fn frames_per_page(&self) -> u64 {
+        (self.kernel_page_size / default_page_size()).max(1)
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.permissions.read
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.permissions.write
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.permissions.execute
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.permissions.shared
+    }
 }
 
 pub fn read(process: &Process) -> Result<Vec<Map>> {
@@ -94,12 +165,23 @@ pub fn read(process: &Process) -> Result<Vec<Map>> {
     let file = File::open(path)?;
     let read = BufReader::new(file);
 
-    let mut maps = Vec::new();
+    let mut maps: Vec<Map> = Vec::new();
 
     for res in read.lines() {
         maps.push(res?.as_str().try_into()?);
     }
 
+    // Huge-page-backed mappings aren't distinguishable from `/proc/<pid>/maps` alone, so
+    // pull their true page size from smaps. This is best-effort: smaps isn't available on
+    // every kernel config, so fall back to the default (every page is `default_page_size()`).
+    if let Ok(sizes) = smaps::kernel_page_sizes(process.pid) {
+        for map in &mut maps {
+            if let Some(size) = sizes.get(&map.address_range) {
+                map.kernel_page_size = *size;
+            }
+        }
+    }
+
     Ok(maps)
 }
 
@@ -115,7 +197,7 @@ impl TryInto<Map> for &str {
 
         Ok(Map {
             address_range: Range { start: u64::from_str_radix(captures.name("from").unwrap().as_str(), 16)?, end: u64::from_str_radix(captures.name("to").unwrap().as_str(), 16)? },
-            permissions: INTERNER.get_or_intern(captures.name("permissions").unwrap().as_str()),
+            permissions: Permissions::parse(captures.name("permissions").unwrap().as_str())?,
             offset: u64::from_str_radix(captures.name("offset").unwrap().as_str(), 16)?,
             device: INTERNER.get_or_intern(captures.name("dev").unwrap().as_str()),
             inode: captures
@@ -125,6 +207,7 @@ impl TryInto<Map> for &str {
                 .to_owned()
                 .parse()?,
             path: captures.name("path").map(|p| INTERNER.get_or_intern(p.as_str())),
+            kernel_page_size: default_page_size(),
         })
     }
 }
@@ -143,11 +226,12 @@ mod tests {
             map,
             Map {
                 address_range: Range { start: 2097152, end: 2248704 },
-                permissions: INTERNER.get_or_intern("r--p"),
+                permissions: Permissions { read: true, write: false, execute: false, shared: false },
                 offset: 0,
                 device: INTERNER.get_or_intern("00:12"),
                 inode: 281474977421407,
                 path: Some(INTERNER.get_or_intern("/init")),
+                kernel_page_size: default_page_size(),
             }
         );
 
@@ -158,15 +242,59 @@ mod tests {
     fn test_page_offsets() {
         let offsets: Vec<u64> = Map {
             address_range: Range { start: 0, end: 0x2000 },
-            permissions: INTERNER.get_or_intern(""),
+            permissions: Permissions { read: false, write: false, execute: false, shared: false },
             offset: 0,
             device: INTERNER.get_or_intern("00:00"),
             inode: 0,
             path: None,
+            kernel_page_size: default_page_size(),
         }
         .page_offsets()
         .collect();
 
         assert_eq!(offsets, vec![0, 8]);
     }
+
+    #[test]
+    fn test_page_offsets_huge_page() {
+        // A 2 MiB huge page mapping should only be visited once in the pagemap walk,
+        // rather than once per 4 KiB constituent page.
+        let huge_page_size = default_page_size() * 512;
+        let offsets: Vec<u64> = Map {
+            address_range: Range { start: 0, end: huge_page_size },
+            permissions: Permissions { read: false, write: false, execute: false, shared: false },
+            offset: 0,
+            device: INTERNER.get_or_intern("00:00"),
+            inode: 0,
+            path: None,
+            kernel_page_size: huge_page_size,
+        }
+        .page_offsets()
+        .collect();
+
+        assert_eq!(offsets, vec![0]);
+    }
+
+    #[test]
+    fn test_permissions_parse() -> Result<()> {
+        assert_eq!(
+            Permissions::parse("rwxp")?,
+            Permissions { read: true, write: true, execute: true, shared: false }
+        );
+        assert_eq!(
+            Permissions::parse("r--s")?,
+            Permissions { read: true, write: false, execute: false, shared: true }
+        );
+        assert_eq!(
+            Permissions::parse("----")?,
+            Permissions { read: false, write: false, execute: false, shared: false }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_permissions_parse_wrong_length() {
+        assert!(Permissions::parse("rwx").is_err());
+    }
 }
@@ -0,0 +1,4 @@
+pub mod kpageflags;
+pub mod maps;
+pub mod pagemap;
+pub mod smaps;
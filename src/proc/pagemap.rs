@@ -7,21 +7,28 @@ use std::{
     path::Path, collections::BTreeMap,
 };
 
+// Bit 61 of a pagemap entry is documented as both "file-mapped or shared-anon" and
+// "shared anonymous", which a per-PID pagemap can't actually tell apart -- that
+// distinction lives in the kernel's global page tables. Rather than exposing an
+// accessor that's ambiguous by construction, use `proc::kpageflags`/`classify_share_kind`
+// for that classification instead.
 bitfield! {
     #[derive(PartialEq)]
     pub struct Page(u64);
     pub in_ram, _: 63;
     pub in_swap, _: 62;
-    pub is_file_mapped, _: 61;
-    pub is_shared_anonymous, _: 61;
     pub is_exclusively_mapped, _: 56;
     pub is_soft_dirty, _: 55;
     pub u64, page_frame_number, _: 54, 0;
+    // Only meaningful when `in_swap()` is set: bits 0-54 are not a page frame number for a
+    // swapped page, they're the swap type and offset instead.
+    pub u64, swap_type, _: 4, 0;
+    pub u64, swap_offset, _: 54, 5;
 }
 
 impl Debug for Page {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Page {{ in_ram: {}, in_swap: {}, is_file_mapped: {}, is_shared_anonymous: {}, is_exclusively_mapped: {}, is_soft_dirty: {}, page_frame_number: {} }}", self.in_ram(), self.in_swap(), self.is_file_mapped(), self.is_shared_anonymous(), self.is_exclusively_mapped(), self.is_soft_dirty(), self.page_frame_number())
+        write!(f, "Page {{ in_ram: {}, in_swap: {}, is_exclusively_mapped: {}, is_soft_dirty: {}, page_frame_number: {}, swap_type: {}, swap_offset: {} }}", self.in_ram(), self.in_swap(), self.is_exclusively_mapped(), self.is_soft_dirty(), self.page_frame_number(), self.swap_type(), self.swap_offset())
     }
 }
 
@@ -30,11 +37,9 @@ pub fn from(pid: u64, maps: &[Map]) -> Result<BTreeMap<Map, Vec<Page>>>
     let path = Path::new("/proc").join(pid.to_string()).join("pagemap");
     let file = File::open(path)?;
     let mut read = BufReader::new(file);
-    let mut buf = [0 as u8; 8];
+    let mut buf = [0u8; 8];
     let mut ret = BTreeMap::new();
 
-    println!("maps len: {}", maps.len());
-
     for map in maps {
         let mut pages = Vec::new();
         for offset in map.page_offsets() {
@@ -44,11 +49,9 @@ pub fn from(pid: u64, maps: &[Map]) -> Result<BTreeMap<Map, Vec<Page>>>
                 .context(format!("failed to read from page {} in pagemap", offset))?;
             pages.push(Page(u64::from_le_bytes(buf)));
         }
-        ret.insert(map.clone(), pages);
+        ret.insert(*map, pages);
     }
 
-    println!("ret len: {}", ret.len());
-
     Ok(ret)
 }
 
@@ -62,8 +65,6 @@ mod tests {
         let page = Page(0);
         assert_eq!(page.in_ram(), false);
         assert_eq!(page.in_swap(), false);
-        assert_eq!(page.is_file_mapped(), false);
-        assert_eq!(page.is_shared_anonymous(), false);
         assert_eq!(page.is_exclusively_mapped(), false);
         assert_eq!(page.is_soft_dirty(), false);
         assert_eq!(page.page_frame_number(), 0);
@@ -74,10 +75,18 @@ mod tests {
         let page = Page(1);
         assert_eq!(page.in_ram(), false);
         assert_eq!(page.in_swap(), false);
-        assert_eq!(page.is_file_mapped(), false);
-        assert_eq!(page.is_shared_anonymous(), false);
         assert_eq!(page.is_exclusively_mapped(), false);
         assert_eq!(page.is_soft_dirty(), false);
         assert_eq!(page.page_frame_number(), 1);
     }
+
+    #[test]
+    fn test_swap_entry() {
+        // in_swap set, swap_type = 3, swap_offset = 12345
+        let page = Page((1u64 << 62) | (12345 << 5) | 3);
+        assert_eq!(page.in_ram(), false);
+        assert_eq!(page.in_swap(), true);
+        assert_eq!(page.swap_type(), 3);
+        assert_eq!(page.swap_offset(), 12345);
+    }
 }
@@ -0,0 +1,71 @@
+use crate::proc::maps::Range;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+lazy_static! {
+    static ref HEADER_RE: Regex = Regex::new(r"^(?P<from>[0-9a-f]+)-(?P<to>[0-9a-f]+)\s").unwrap();
+    static ref KERNEL_PAGE_SIZE_RE: Regex =
+        Regex::new(r"^KernelPageSize:\s+(?P<kb>[0-9]+)\s*kB").unwrap();
+}
+
+/// Parses `/proc/<pid>/smaps` into a map from each mapping's address range to its
+/// `KernelPageSize` in bytes. For normal mappings this is just the system page size, but
+/// for mappings backed by transparent or explicit huge pages it's the true 2 MiB/1 GiB
+/// page size, which `proc::maps::Map` can't determine from `/proc/<pid>/maps` alone.
+pub fn kernel_page_sizes(pid: u64) -> Result<BTreeMap<Range, u64>> {
+    let path = Path::new("/proc").join(pid.to_string()).join("smaps");
+    let file = File::open(path)?;
+    let read = BufReader::new(file);
+
+    let mut ret = BTreeMap::new();
+    let mut current: Option<Range> = None;
+
+    for line in read.lines() {
+        let line = line?;
+
+        if let Some(captures) = HEADER_RE.captures(&line) {
+            current = Some(Range {
+                start: u64::from_str_radix(&captures["from"], 16)?,
+                end: u64::from_str_radix(&captures["to"], 16)?,
+            });
+            continue;
+        }
+
+        if let Some(captures) = KERNEL_PAGE_SIZE_RE.captures(&line) {
+            if let Some(range) = current {
+                let kb: u64 = captures["kb"].parse()?;
+                ret.insert(range, kb * 1024);
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_kernel_page_size_regex() {
+        let captures = KERNEL_PAGE_SIZE_RE.captures("KernelPageSize:     2048 kB").unwrap();
+        assert_eq!(&captures["kb"], "2048");
+    }
+
+    #[test]
+    fn test_header_regex() {
+        let captures = HEADER_RE
+            .captures("00200000-00225000 r--p 00000000 00:12 281474977421407 /init")
+            .unwrap();
+        assert_eq!(&captures["from"], "00200000");
+        assert_eq!(&captures["to"], "00225000");
+    }
+}
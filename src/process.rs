@@ -24,7 +24,7 @@ impl PartialEq for Process {
 
 impl PartialOrd for Process {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.pid.partial_cmp(&other.pid)
+        Some(self.cmp(other))
     }
 }
 
@@ -52,11 +52,31 @@ impl Process {
         Ok(Self { pid, cmdline: INTERNER.get_or_intern(cmdline) })
     }
 
+    pub fn cmdline(&self) -> &str {
+        INTERNER.resolve(&self.cmdline)
+    }
+
+    pub(crate) fn from_parts(pid: u64, cmdline: String) -> Self {
+        Self { pid, cmdline: INTERNER.get_or_intern(cmdline) }
+    }
+
     pub fn read_pages(&self) -> Result<BTreeMap<Map, Vec<Page>>> {
-        let maps = proc::maps::read(&self)?;
+        let maps = proc::maps::read(self)?;
         let pages = proc::pagemap::from(self.pid, &maps)?;
         Ok(pages)
     }
+
+    /// Clears the soft-dirty bit on every page in this process, per
+    /// `Documentation/admin-guide/mm/soft-dirty.rst`. Subsequent writes will set the bit
+    /// again, so a snapshot taken after this call can be diffed against a later one to see
+    /// which pages were touched in between.
+    pub fn clear_soft_dirty(&self) -> Result<()> {
+        std::fs::write(
+            Path::new("/proc").join(self.pid.to_string()).join("clear_refs"),
+            "4\n",
+        )?;
+        Ok(())
+    }
 }
 
 pub fn all() -> Result<Vec<Process>> {
@@ -68,12 +88,12 @@ pub fn all() -> Result<Vec<Process>> {
         }
 
         let path = dir.path();
-        let file_name = match path.components().last() {
+        let file_name = match path.components().next_back() {
             Some(name) => name.as_os_str().to_string_lossy(),
             None => continue,
         };
 
-        let pid = match u64::from_str_radix(&file_name, 10) {
+        let pid = match file_name.parse::<u64>() {
             Ok(pid) => pid,
             Err(_) => continue,
         };
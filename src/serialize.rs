@@ -0,0 +1,210 @@
+use crate::process::Process;
+use crate::{PageRange, ShareKind};
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+};
+
+/// Writes `Self` to a byte stream using little-endian primitives, with no external
+/// dependency on a derive-based binary format crate -- this keeps the on-disk layout
+/// explicit and easy to reason about.
+pub trait ToWriter {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()>;
+}
+
+/// The `ToWriter` counterpart: reconstructs `Self` from a byte stream written by
+/// `ToWriter::to_writer`.
+pub trait FromReader: Sized {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self>;
+}
+
+impl ToWriter for u64 {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl FromReader for u64 {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl ToWriter for PageRange {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.from.to_writer(writer)?;
+        self.to.to_writer(writer)?;
+        Ok(())
+    }
+}
+
+impl FromReader for PageRange {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        Ok(PageRange {
+            from: u64::from_reader(reader)?,
+            to: u64::from_reader(reader)?,
+        })
+    }
+}
+
+impl ToWriter for ShareKind {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let tag: u8 = match self {
+            ShareKind::FileBacked => 0,
+            ShareKind::AnonymousShared => 1,
+            ShareKind::KsmMerged => 2,
+            ShareKind::Unknown => 3,
+        };
+        writer.write_all(&[tag])?;
+        Ok(())
+    }
+}
+
+impl FromReader for ShareKind {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf)?;
+        Ok(match buf[0] {
+            0 => ShareKind::FileBacked,
+            1 => ShareKind::AnonymousShared,
+            2 => ShareKind::KsmMerged,
+            3 => ShareKind::Unknown,
+            other => return Err(anyhow!("unknown ShareKind tag {}", other)),
+        })
+    }
+}
+
+impl ToWriter for Process {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.pid.to_writer(writer)?;
+        let cmdline = self.cmdline();
+        (cmdline.len() as u64).to_writer(writer)?;
+        writer.write_all(cmdline.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl FromReader for Process {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let pid = u64::from_reader(reader)?;
+        let len = u64::from_reader(reader)?;
+        let mut buf = vec![0u8; len as usize];
+        reader.read_exact(&mut buf)?;
+        let cmdline = String::from_utf8(buf)?;
+        Ok(Process::from_parts(pid, cmdline))
+    }
+}
+
+impl<T: ToWriter> ToWriter for BTreeSet<T> {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        (self.len() as u64).to_writer(writer)?;
+        for item in self {
+            item.to_writer(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: FromReader + Ord> FromReader for BTreeSet<T> {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let len = u64::from_reader(reader)?;
+        let mut set = BTreeSet::new();
+        for _ in 0..len {
+            set.insert(T::from_reader(reader)?);
+        }
+        Ok(set)
+    }
+}
+
+impl<A: ToWriter, B: ToWriter> ToWriter for (A, B) {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.0.to_writer(writer)?;
+        self.1.to_writer(writer)?;
+        Ok(())
+    }
+}
+
+impl<A: FromReader, B: FromReader> FromReader for (A, B) {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        Ok((A::from_reader(reader)?, B::from_reader(reader)?))
+    }
+}
+
+impl<K: ToWriter, V: ToWriter> ToWriter for BTreeMap<K, V> {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        (self.len() as u64).to_writer(writer)?;
+        for (key, value) in self {
+            key.to_writer(writer)?;
+            value.to_writer(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<K: FromReader + Ord, V: FromReader> FromReader for BTreeMap<K, V> {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let len = u64::from_reader(reader)?;
+        let mut map = BTreeMap::new();
+        for _ in 0..len {
+            let key = K::from_reader(reader)?;
+            let value = V::from_reader(reader)?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maplit::btreeset;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_page_range_roundtrip() -> Result<()> {
+        let range = PageRange { from: 4096, to: 8192 };
+        let mut buf = Vec::new();
+        range.to_writer(&mut buf)?;
+
+        let mut cursor = Cursor::new(buf);
+        let decoded = PageRange::from_reader(&mut cursor)?;
+
+        assert_eq!(decoded, range);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_roundtrip() -> Result<()> {
+        let mut report: BTreeMap<PageRange, (ShareKind, BTreeSet<Process>)> = BTreeMap::new();
+        report.insert(
+            PageRange { from: 0, to: 4096 },
+            (
+                ShareKind::FileBacked,
+                btreeset! { Process::from_parts(1234, "/bin/init".to_owned()) },
+            ),
+        );
+
+        let mut buf = Vec::new();
+        report.to_writer(&mut buf)?;
+
+        let mut cursor = Cursor::new(buf);
+        let decoded: BTreeMap<PageRange, (ShareKind, BTreeSet<Process>)> =
+            FromReader::from_reader(&mut cursor)?;
+
+        assert_eq!(decoded, report);
+
+        // `assert_eq!` above only exercises `Process::eq`, which compares `pid` alone --
+        // assert on the resolved cmdline explicitly so a bug that corrupts it in
+        // `to_writer`/`from_reader` can't slip through unnoticed.
+        let (_, pids) = decoded.get(&PageRange { from: 0, to: 4096 }).unwrap();
+        assert_eq!(pids.iter().next().unwrap().cmdline(), "/bin/init");
+
+        Ok(())
+    }
+}